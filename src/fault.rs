@@ -0,0 +1,116 @@
+//! Overcurrent/overvoltage protection: a dedicated task that continuously samples a DC-link
+//! current shunt and an overvoltage comparator, and immediately tri-states all three half bridges
+//! and latches a fault the moment either trips.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use embassy_rp::adc::Channel as AdcChannel;
+use embassy_rp::gpio::Input;
+use embassy_time::{Duration, Ticker};
+
+use crate::{PhaseCommand, SharedAdc, SharedBridges};
+
+/// How often the protection task samples the current shunt. Close to the 40 us PWM period at
+/// 25 kHz, so a trip forces the bridges to high impedance within a couple of PWM periods.
+const WATCHDOG_PERIOD: Duration = Duration::from_micros(50);
+
+/// Trip threshold for the protection task, expressed in raw ADC counts to avoid pulling a
+/// floating-point conversion into the watchdog's hot path. Overvoltage is sensed by a hardware
+/// comparator instead, so it needs no software threshold.
+pub struct ProtectionConfig {
+    /// DC-link shunt ADC count above which the fault latches (overcurrent)
+    pub phase_current_trip: u16,
+}
+
+impl Default for ProtectionConfig {
+    fn default() -> Self {
+        Self {
+            phase_current_trip: 3800,
+        }
+    }
+}
+
+/// Latched fault state, observed by the active control task and cleared only by an explicit,
+/// deliberate re-enable rather than automatically once the offending condition subsides.
+pub struct FaultLatch {
+    tripped: AtomicBool,
+}
+
+impl FaultLatch {
+    pub const fn new() -> Self {
+        Self {
+            tripped: AtomicBool::new(false),
+        }
+    }
+
+    fn trip(&self) {
+        self.tripped.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_tripped(&self) -> bool {
+        self.tripped.load(Ordering::Relaxed)
+    }
+
+    /// Explicitly clears a latched fault. The caller is responsible for having verified the
+    /// underlying condition has actually cleared before calling this.
+    pub fn clear_fault(&self) {
+        self.tripped.store(false, Ordering::Relaxed);
+    }
+}
+
+/// Forces all three half bridges to high impedance, used both on a fresh trip and on every
+/// watchdog tick while a fault remains latched so nothing can re-drive the bridges in between.
+fn trip_bridges(bridges: &SharedBridges) {
+    bridges.lock(|cell| {
+        let mut bridges = cell.borrow_mut();
+        bridges.0.apply(PhaseCommand::high_impedance());
+        bridges.1.apply(PhaseCommand::high_impedance());
+        bridges.2.apply(PhaseCommand::high_impedance());
+    });
+}
+
+/// Watchdog task, spawned on the same executor as the control tasks but independent of whichever
+/// one is active: samples the DC-link
+/// current shunt and the overvoltage comparator on its own ticker, tripping the latch and forcing
+/// the bridges to high impedance the moment either fires.
+#[embassy_executor::task]
+pub async fn protection_task(
+    adc: &'static SharedAdc,
+    mut current_sense: AdcChannel<'static>,
+    overvoltage_comparator: Input<'static>,
+    bridges: &'static SharedBridges,
+    fault: &'static FaultLatch,
+    config: ProtectionConfig,
+) {
+    let mut ticker = Ticker::every(WATCHDOG_PERIOD);
+
+    loop {
+        ticker.next().await;
+
+        let phase_current = {
+            let mut adc = adc.lock().await;
+            adc.read(&mut current_sense).await.unwrap_or(0)
+        };
+
+        if fault.is_tripped() {
+            trip_bridges(bridges);
+            continue;
+        }
+
+        if phase_current > config.phase_current_trip || overvoltage_comparator.is_high() {
+            fault.trip();
+            trip_bridges(bridges);
+        }
+    }
+}
+
+/// Watches an active-low re-enable button and explicitly clears a latched fault on each press.
+/// This is the only path that calls `FaultLatch::clear_fault`: there's no automatic recovery, so
+/// a tripped inverter stays in high impedance until someone deliberately acknowledges it.
+#[embassy_executor::task]
+pub async fn fault_clear_task(mut reenable_button: Input<'static>, fault: &'static FaultLatch) {
+    loop {
+        reenable_button.wait_for_falling_edge().await;
+        fault.clear_fault();
+    }
+}
@@ -0,0 +1,39 @@
+//! Small rolling filters shared by the back-EMF and Hall-effect angle sensors.
+
+use embassy_time::Duration;
+
+/// Rolling median filter over the last `N` measured time intervals, used to reject sampling
+/// noise on sensed commutation/edge timestamps without the phase lag of a low-pass filter.
+pub struct IntervalFilter<const N: usize> {
+    samples: [Duration; N],
+    len: usize,
+    next: usize,
+}
+
+impl<const N: usize> IntervalFilter<N> {
+    pub const fn new() -> Self {
+        Self {
+            samples: [Duration::from_ticks(0); N],
+            len: 0,
+            next: 0,
+        }
+    }
+
+    /// Records a newly measured interval
+    pub fn push(&mut self, interval: Duration) {
+        self.samples[self.next] = interval;
+        self.next = (self.next + 1) % N;
+        self.len = (self.len + 1).min(N);
+    }
+
+    /// Returns the median of the recorded intervals, or `None` if no interval has been recorded yet
+    pub fn median(&self) -> Option<Duration> {
+        if self.len == 0 {
+            return None;
+        }
+
+        let mut sorted = self.samples;
+        sorted[..self.len].sort_unstable_by_key(|d| d.as_ticks());
+        Some(sorted[self.len / 2])
+    }
+}
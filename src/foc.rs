@@ -0,0 +1,246 @@
+//! Field-oriented control: Clarke/Park transforms, current regulators and space-vector
+//! modulation, used as an alternative to the six-step trapezoidal commutation table.
+
+use core::f32::consts::{FRAC_PI_3, PI};
+
+use embassy_rp::gpio::Input;
+use embassy_time::Instant;
+use libm::{cosf, sinf};
+
+use crate::filter::IntervalFilter;
+
+/// Twice PI, i.e. one full electrical revolution and the wrap point for electrical angle in radians
+const TWO_PI: f32 = 2.0 * PI;
+/// One electrical sector is 60 degrees, the spacing between Hall sensor edges
+const SECTOR_ANGLE: f32 = FRAC_PI_3;
+/// Number of past Hall edge intervals kept for median filtering of the sector period
+const HALL_FILTER_LEN: usize = 3;
+
+/// Clarke transform: projects the three phase currents onto the stationary two-axis
+/// (alpha, beta) frame. Only two phase currents need to be sensed since `ia + ib + ic == 0`.
+pub fn clarke(i_a: f32, i_b: f32) -> (f32, f32) {
+    let i_alpha = i_a;
+    let i_beta = (i_a + 2.0 * i_b) / libm::sqrtf(3.0);
+    (i_alpha, i_beta)
+}
+
+/// Park transform: rotates the stationary (alpha, beta) frame into the rotor (d, q) frame
+/// given the electrical rotor angle `theta` in radians.
+pub fn park(i_alpha: f32, i_beta: f32, theta: f32) -> (f32, f32) {
+    let (sin_theta, cos_theta) = (sinf(theta), cosf(theta));
+    let i_d = i_alpha * cos_theta + i_beta * sin_theta;
+    let i_q = -i_alpha * sin_theta + i_beta * cos_theta;
+    (i_d, i_q)
+}
+
+/// Inverse Park transform: rotates a (d, q) voltage command back into the stationary
+/// (alpha, beta) frame for space-vector modulation.
+pub fn inverse_park(v_d: f32, v_q: f32, theta: f32) -> (f32, f32) {
+    let (sin_theta, cos_theta) = (sinf(theta), cosf(theta));
+    let v_alpha = v_d * cos_theta - v_q * sin_theta;
+    let v_beta = v_d * sin_theta + v_q * cos_theta;
+    (v_alpha, v_beta)
+}
+
+/// A PI regulator with anti-windup, used for the d-axis/q-axis current loops and the electrical
+/// speed loop. `output_min`/`output_max` may be asymmetric (e.g. a duty cycle floored above zero).
+pub struct PiRegulator {
+    kp: f32,
+    ki: f32,
+    integral: f32,
+    output_min: f32,
+    output_max: f32,
+}
+
+impl PiRegulator {
+    pub const fn new(kp: f32, ki: f32, output_min: f32, output_max: f32) -> Self {
+        Self {
+            kp,
+            ki,
+            integral: 0.0,
+            output_min,
+            output_max,
+        }
+    }
+
+    /// Advances the regulator by one step of `dt` seconds given the current error, returning
+    /// the clamped control output. The integral only accumulates within `output_min..=output_max`.
+    pub fn update(&mut self, error: f32, dt: f32) -> f32 {
+        let proportional = self.kp * error;
+        let candidate_integral = self.integral + self.ki * error * dt;
+        let unclamped = proportional + candidate_integral;
+
+        if unclamped >= self.output_min && unclamped <= self.output_max {
+            self.integral = candidate_integral;
+        }
+
+        (proportional + self.integral).clamp(self.output_min, self.output_max)
+    }
+
+    pub fn reset(&mut self) {
+        self.integral = 0.0;
+    }
+}
+
+/// Per-phase duty cycle commands produced by space-vector modulation, as a fraction of the
+/// PWM period in the range `0.0..=1.0`.
+#[derive(Copy, Clone)]
+pub struct SvmDuties {
+    pub phase_a: f32,
+    pub phase_b: f32,
+    pub phase_c: f32,
+}
+
+/// Space-vector modulation: given a voltage vector (`v_alpha`, `v_beta`) and the bus voltage,
+/// determines the active sector, the on-times of the two adjacent active vectors and the null
+/// vector, and returns the resulting per-phase duty cycles.
+pub fn space_vector_modulate(v_alpha: f32, v_beta: f32, v_bus: f32) -> SvmDuties {
+    // Identify which of the six 60 degree sectors the voltage vector falls in.
+    let raw_angle = libm::atan2f(v_beta, v_alpha);
+    let angle = raw_angle - TWO_PI * libm::floorf(raw_angle / TWO_PI);
+    let sector = (angle / FRAC_PI_3) as u8;
+
+    // Project the vector onto the two active vectors bounding this sector; sqrt(3) converts
+    // the magnitude into the SVPWM modulation index.
+    let theta = angle - sector as f32 * FRAC_PI_3;
+    let magnitude = libm::hypotf(v_alpha, v_beta) * libm::sqrtf(3.0) / v_bus;
+
+    let t1 = magnitude * libm::sinf(FRAC_PI_3 - theta);
+    let t2 = magnitude * libm::sinf(theta);
+    let t0 = (1.0 - t1 - t2).max(0.0);
+
+    // Each sector maps the two active-vector on-times onto the three phases differently; the
+    // null-vector time is split evenly at the start and end of the period (centre-aligned PWM).
+    let (ta, tb, tc) = match sector {
+        0 => (t1 + t2 + t0 / 2.0, t2 + t0 / 2.0, t0 / 2.0),
+        1 => (t1 + t0 / 2.0, t1 + t2 + t0 / 2.0, t0 / 2.0),
+        2 => (t0 / 2.0, t1 + t2 + t0 / 2.0, t2 + t0 / 2.0),
+        3 => (t0 / 2.0, t1 + t0 / 2.0, t1 + t2 + t0 / 2.0),
+        4 => (t2 + t0 / 2.0, t0 / 2.0, t1 + t2 + t0 / 2.0),
+        _ => (t1 + t2 + t0 / 2.0, t0 / 2.0, t1 + t0 / 2.0),
+    };
+
+    SvmDuties {
+        phase_a: ta.clamp(0.0, 1.0),
+        phase_b: tb.clamp(0.0, 1.0),
+        phase_c: tc.clamp(0.0, 1.0),
+    }
+}
+
+/// Closed-loop field-oriented current controller: regulates `i_d` to zero and `i_q` to a
+/// commanded reference, producing the per-phase SVM duty cycles for one control period.
+pub struct FocController {
+    id_regulator: PiRegulator,
+    iq_regulator: PiRegulator,
+}
+
+impl FocController {
+    pub const fn new(kp: f32, ki: f32, voltage_limit: f32) -> Self {
+        Self {
+            id_regulator: PiRegulator::new(kp, ki, -voltage_limit, voltage_limit),
+            iq_regulator: PiRegulator::new(kp, ki, -voltage_limit, voltage_limit),
+        }
+    }
+
+    /// Runs one control period: takes the two measured phase currents and the electrical
+    /// rotor angle, regulates towards `iq_reference` with `id` held at zero, and returns the
+    /// space-vector modulated duty cycles to apply to the three half bridges.
+    pub fn update(
+        &mut self,
+        i_a: f32,
+        i_b: f32,
+        theta: f32,
+        iq_reference: f32,
+        v_bus: f32,
+        dt: f32,
+    ) -> SvmDuties {
+        let (i_alpha, i_beta) = clarke(i_a, i_b);
+        let (i_d, i_q) = park(i_alpha, i_beta, theta);
+
+        let v_d = self.id_regulator.update(0.0 - i_d, dt);
+        let v_q = self.iq_regulator.update(iq_reference - i_q, dt);
+
+        let (v_alpha, v_beta) = inverse_park(v_d, v_q, theta);
+        space_vector_modulate(v_alpha, v_beta, v_bus)
+    }
+
+    pub fn reset(&mut self) {
+        self.id_regulator.reset();
+        self.iq_regulator.reset();
+    }
+}
+
+/// Decodes three digital Hall-effect sensors into an electrical rotor angle, the rotor-angle
+/// source for FOC.
+pub struct HallAngleSensor<'d> {
+    hall_a: Input<'d>,
+    hall_b: Input<'d>,
+    hall_c: Input<'d>,
+    last_edge: Option<Instant>,
+    intervals: IntervalFilter<HALL_FILTER_LEN>,
+    last_sector: u8,
+}
+
+/// Maps the 6 valid Hall sensor bit patterns (`hall_c << 2 | hall_b << 1 | hall_a`) to the
+/// electrical sector they indicate. The two unused patterns (0 and 7) mean a sensor fault.
+const HALL_SECTOR_TABLE: [Option<u8>; 8] = [
+    None,
+    Some(0),
+    Some(2),
+    Some(1),
+    Some(4),
+    Some(5),
+    Some(3),
+    None,
+];
+
+impl<'d> HallAngleSensor<'d> {
+    pub fn new(hall_a: Input<'d>, hall_b: Input<'d>, hall_c: Input<'d>) -> Self {
+        Self {
+            hall_a,
+            hall_b,
+            hall_c,
+            last_edge: None,
+            intervals: IntervalFilter::new(),
+            last_sector: 0,
+        }
+    }
+
+    fn pattern(&self) -> usize {
+        (self.hall_c.is_high() as usize) << 2
+            | (self.hall_b.is_high() as usize) << 1
+            | (self.hall_a.is_high() as usize)
+    }
+
+    /// Waits for the next Hall transition and updates the measured sector and period. Run
+    /// concurrently with the fixed-rate FOC control loop via `embassy_futures::select`.
+    pub async fn wait_for_edge(&mut self) {
+        embassy_futures::select::select3(
+            self.hall_a.wait_for_any_edge(),
+            self.hall_b.wait_for_any_edge(),
+            self.hall_c.wait_for_any_edge(),
+        )
+        .await;
+
+        let now = Instant::now();
+        if let Some(sector) = HALL_SECTOR_TABLE[self.pattern()] {
+            self.last_sector = sector;
+        }
+
+        if let Some(previous) = self.last_edge.replace(now) {
+            self.intervals.push(now - previous);
+        }
+    }
+
+    /// Interpolates the current electrical angle from the last Hall edge using the filtered
+    /// sector period. Returns `None` until at least one full sector has been measured.
+    pub fn angle(&self, now: Instant) -> Option<f32> {
+        let period = self.intervals.median()?;
+        let last_edge = self.last_edge?;
+
+        let elapsed = now - last_edge;
+        let fraction = elapsed.as_ticks() as f32 / period.as_ticks().max(1) as f32;
+
+        Some((self.last_sector as f32 * SECTOR_ANGLE + fraction * SECTOR_ANGLE) % TWO_PI)
+    }
+}
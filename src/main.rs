@@ -1,81 +1,207 @@
 #![no_std]
 #![no_main]
 
+mod fault;
+mod filter;
+mod foc;
+mod speed;
+
+use core::cell::RefCell;
 use core::marker::PhantomData;
 
 use embassy_executor::Spawner;
-use embassy_rp::gpio::{Level, Output};
+use embassy_rp::adc::{Adc, Async, Channel as AdcChannel, Config as AdcConfig, InterruptHandler as AdcInterruptHandler};
+use embassy_rp::bind_interrupts;
+use embassy_rp::gpio::{Input, Level, Output, Pull};
 use embassy_rp::pwm::{ChannelAPin, ChannelBPin, Config, Pwm, PwmOutput, SetDutyCycle, Slice};
 use embassy_rp::Peri;
-use embassy_time::{Duration, Ticker};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex as BlockingMutex;
+use embassy_sync::mutex::Mutex as AsyncMutex;
+use embassy_time::{Duration, Instant, Ticker};
+use static_cell::StaticCell;
 use {defmt_rtt as _, panic_probe as _};
 
-/// Fully on duty cycle
-const FULLY_ON_DUTY_CYCLE: u8 = 100;
-/// Max duty cycle for driving inverter phases
-const MAX_INVERTER_DUTY_CYCLE: u8 = 15;
-/// Min duty cycle for driving inverter phases
-const MIN_INVERTER_DUTY_CYCLE: u8 = 0;
-
-/// Lookup table for phase voltages when commuting a three phase inverter.
-const THREE_PHASE_COMMUTATION_TABLE: [InverterOutput; 6] = [
-    InverterOutput {
-        phase_a: PhaseState::HighDutyCycle(MAX_INVERTER_DUTY_CYCLE),
-        phase_b: PhaseState::Low,
-        phase_c: PhaseState::HighImpedance,
-    },
-    InverterOutput {
-        phase_a: PhaseState::HighDutyCycle(MAX_INVERTER_DUTY_CYCLE),
-        phase_b: PhaseState::HighImpedance,
-        phase_c: PhaseState::Low,
-    },
-    InverterOutput {
-        phase_a: PhaseState::HighImpedance,
-        phase_b: PhaseState::HighDutyCycle(MAX_INVERTER_DUTY_CYCLE),
-        phase_c: PhaseState::Low,
-    },
-    InverterOutput {
-        phase_a: PhaseState::Low,
-        phase_b: PhaseState::HighDutyCycle(MAX_INVERTER_DUTY_CYCLE),
-        phase_c: PhaseState::HighImpedance,
-    },
-    InverterOutput {
-        phase_a: PhaseState::Low,
-        phase_b: PhaseState::HighImpedance,
-        phase_c: PhaseState::HighDutyCycle(MAX_INVERTER_DUTY_CYCLE),
-    },
-    InverterOutput {
-        phase_a: PhaseState::HighImpedance,
-        phase_b: PhaseState::Low,
-        phase_c: PhaseState::HighDutyCycle(MAX_INVERTER_DUTY_CYCLE),
-    },
+/// The ADC peripheral, and its associated interrupt, is shared by whichever control task is
+/// sampling phase signals and the fault protection task sampling the current shunt.
+pub(crate) type SharedAdc = AsyncMutex<CriticalSectionRawMutex, Adc<'static, Async>>;
+
+/// The three half bridges, shared between the active control task and the fault protection
+/// task so a trip can force them to high impedance regardless of what the control task is doing.
+pub(crate) type BridgeSet = (
+    HalfBridge<'static, embassy_rp::peripherals::PWM_SLICE2>,
+    HalfBridge<'static, embassy_rp::peripherals::PWM_SLICE6>,
+    HalfBridge<'static, embassy_rp::peripherals::PWM_SLICE7>,
+);
+pub(crate) type SharedBridges = BlockingMutex<CriticalSectionRawMutex, RefCell<BridgeSet>>;
+
+bind_interrupts!(struct Irqs {
+    ADC_IRQ_FIFO => AdcInterruptHandler;
+});
+
+/// Max duty cycle for driving inverter phases, as a percentage of the configured PWM `top`.
+const MAX_INVERTER_DUTY_CYCLE_PERCENT: u8 = 15;
+
+/// Initial commutation dwell time used while ramping up open-loop at startup
+const STARTUP_DWELL: Duration = Duration::from_millis(40);
+/// Final (fastest) open-loop dwell time before handing off to closed-loop commutation
+const STARTUP_MIN_DWELL: Duration = Duration::from_millis(4);
+/// Dwell time is multiplied by this fraction (numerator/denominator) after every startup step
+const STARTUP_RAMP_NUM: u32 = 9;
+const STARTUP_RAMP_DEN: u32 = 10;
+/// Number of consecutive zero-crossings required before the speed controller replaces the
+/// startup duty
+const ZERO_CROSSINGS_TO_LOCK: u8 = 6;
+/// Give up waiting for a zero-crossing after this long and fall back to the last known interval
+const ZERO_CROSSING_TIMEOUT: Duration = Duration::from_millis(50);
+/// Number of past commutation intervals kept for median filtering of the zero-crossing period
+const INTERVAL_FILTER_LEN: usize = 3;
+
+/// Electrical speed loop proportional gain
+const SPEED_KP: f32 = 0.05;
+/// Electrical speed loop integral gain
+const SPEED_KI: f32 = 0.5;
+/// Maximum allowed change in the commanded electrical speed per second, used by the ramp limiter
+const SPEED_MAX_ACCEL_HZ_PER_SEC: f32 = 200.0;
+/// Minimum high-side duty cycle, in percent of `top`, commanded once the motor is running;
+/// below this the inverter can't reliably overcome friction/cogging at low speed
+const MIN_RUN_DUTY_PERCENT: u8 = 5;
+/// Default target electrical speed used until an external task commands a different setpoint
+/// over `speed::SPEED_SETPOINT`
+const DEFAULT_TARGET_ELECTRICAL_HZ: f32 = 150.0;
+
+/// Selects which control strategy `main` spawns: six-step trapezoidal commutation or
+/// sinusoidal field-oriented control.
+enum ControlMode {
+    SixStep,
+    Foc,
+}
+
+/// The active control strategy. Flipping this constant swaps the drive task spawned in `main`.
+const CONTROL_MODE: ControlMode = ControlMode::SixStep;
+
+/// Nominal bus voltage used to normalize the FOC voltage commands for space-vector modulation
+const BUS_VOLTAGE: f32 = 12.0;
+/// FOC current loop proportional gain
+const FOC_KP: f32 = 0.6;
+/// FOC current loop integral gain
+const FOC_KI: f32 = 40.0;
+/// FOC control period
+const FOC_PERIOD: Duration = Duration::from_micros(100);
+/// Commanded q-axis current reference (amps) for the FOC torque loop
+const FOC_IQ_REFERENCE: f32 = 2.0;
+
+/// Builds the lookup table for phase voltages when commuting a three phase inverter, given
+/// `max_duty` (the half bridges' shared PWM `top`) in raw timer counts.
+fn commutation_table(max_duty: u16) -> [InverterOutput; 6] {
+    let driven_duty = (max_duty as u32 * MAX_INVERTER_DUTY_CYCLE_PERCENT as u32 / 100) as u16;
+    let driven = PhaseCommand::driven(driven_duty);
+
+    [
+        InverterOutput {
+            phase_a: driven,
+            phase_b: PhaseCommand::low(),
+            phase_c: PhaseCommand::high_impedance(),
+        },
+        InverterOutput {
+            phase_a: driven,
+            phase_b: PhaseCommand::high_impedance(),
+            phase_c: PhaseCommand::low(),
+        },
+        InverterOutput {
+            phase_a: PhaseCommand::high_impedance(),
+            phase_b: driven,
+            phase_c: PhaseCommand::low(),
+        },
+        InverterOutput {
+            phase_a: PhaseCommand::low(),
+            phase_b: driven,
+            phase_c: PhaseCommand::high_impedance(),
+        },
+        InverterOutput {
+            phase_a: PhaseCommand::low(),
+            phase_b: PhaseCommand::high_impedance(),
+            phase_c: driven,
+        },
+        InverterOutput {
+            phase_a: PhaseCommand::high_impedance(),
+            phase_b: PhaseCommand::low(),
+            phase_c: driven,
+        },
+    ]
+}
+
+/// For each commutation step, the floating phase to sense back-EMF on and whether it's
+/// expected to rise or fall through the virtual neutral.
+const ZERO_CROSSING_TABLE: [(Phase, bool); 6] = [
+    (Phase::C, false),
+    (Phase::B, true),
+    (Phase::A, false),
+    (Phase::C, true),
+    (Phase::B, false),
+    (Phase::A, true),
 ];
 
-/// Represents how a half bridge phase should be driven
+/// Identifies one of the three inverter phases, used to index the back-EMF ADC channels.
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Phase {
+    A,
+    B,
+    C,
+}
+
+/// Represents how a half bridge phase should be driven: tri-stated when `enable` is false,
+/// otherwise the high side PWMs at `duty_cycle_high_side` raw timer counts (against the half
+/// bridge's configured `top`) with the low side driven complementary.
 #[derive(Copy, Clone)]
-enum PhaseState {
-    HighDutyCycle(u8),
-    Low,
-    HighImpedance,
+pub(crate) struct PhaseCommand {
+    duty_cycle_high_side: u16,
+    enable: bool,
+}
+
+impl PhaseCommand {
+    /// Drives the high side at `duty_cycle_high_side` raw timer counts, with the low side
+    /// complementary
+    pub(crate) const fn driven(duty_cycle_high_side: u16) -> Self {
+        Self {
+            duty_cycle_high_side,
+            enable: true,
+        }
+    }
+
+    /// Drives the phase low: zero high-side duty, with the low side on except for the
+    /// dead-time window
+    const fn low() -> Self {
+        Self::driven(0)
+    }
+
+    /// Tri-states both gates
+    pub(crate) const fn high_impedance() -> Self {
+        Self {
+            duty_cycle_high_side: 0,
+            enable: false,
+        }
+    }
 }
 
 /// Represents the control outputs for a three phase inverter
 #[derive(Copy, Clone)]
 struct InverterOutput {
-    phase_a: PhaseState,
-    phase_b: PhaseState,
-    phase_c: PhaseState,
+    phase_a: PhaseCommand,
+    phase_b: PhaseCommand,
+    phase_c: PhaseCommand,
 }
 
 /// Represents a half bridge driven by a high side and low side enable pin
-struct HalfBridge<'d, S>
+pub(crate) struct HalfBridge<'d, S>
 where
     S: Slice,
 {
     pwm: Pwm<'d>,
-    divider: u8,
+    complementary_config: Config,
+    high_impedance_config: Config,
     top: u16,
-    dead_time_percentage: u8,
+    dead_time_ticks: u16,
     _slice: PhantomData<S>,
 }
 
@@ -97,7 +223,6 @@ where
         let period = (clock_freq_hz / (pwm_frequency_hz * divider as u32) - 1) as u16 / 2;
         let dead_time_ticks =
             ((dead_time_ns as u64 * clock_freq_hz as u64) / divider as u64 / 1_000_000_000) as u16;
-        let dead_time_percentage = ((dead_time_ticks as u32 * 100) / period as u32) as u8;
 
         // Configure default PWM settings
         let mut config = Config::default();
@@ -110,80 +235,141 @@ where
         config.compare_b = 0;
         config.top = period;
 
-        let pwm = Pwm::new_output_ab(slice, high_pin, low_pin, config);
+        let pwm = Pwm::new_output_ab(slice, high_pin, low_pin, config.clone());
+
+        // Build both configs a half bridge ever needs once: normal complementary PWM drive, and
+        // a tri-stated, high impedance output.
+        let mut complementary_config = config.clone();
+        complementary_config.invert_b = true;
+
+        let high_impedance_config = config.clone();
 
         Self {
-            pwm: pwm,
-            divider: divider,
+            pwm,
+            complementary_config,
+            high_impedance_config,
             top: period,
-            dead_time_percentage: dead_time_percentage,
+            dead_time_ticks,
             _slice: PhantomData,
         }
     }
 
-    /// Set the half bridge to PWM the high side gate to the specified duty cycle
-    fn set_high(&mut self, percentage: u8) {
-        let mut complementary_config = Config::default();
-        complementary_config.invert_a = false;
-        complementary_config.invert_b = true;
-        complementary_config.phase_correct = true;
-        complementary_config.enable = true;
-        complementary_config.divider = self.divider.into();
-        complementary_config.compare_a = 0;
-        complementary_config.compare_b = 0;
-        complementary_config.top = self.top;
-
-        self.pwm.set_config(&complementary_config);
-
-        let (high_pwm, low_pwm) = self.pwm.split_by_ref();
-
-        let _ = high_pwm.unwrap().set_duty_cycle_percent(percentage);
-        let _ = low_pwm
-            .unwrap()
-            .set_duty_cycle_percent(percentage + self.dead_time_percentage);
+    /// The configured PWM `top`, i.e. the maximum valid raw duty compare value
+    pub(crate) fn max_duty(&self) -> u16 {
+        self.top
     }
 
-    /// Set the half bridge to be driven low
-    fn set_low(&mut self) {
-        let mut config = Config::default();
-        config.invert_a = false;
-        config.invert_b = false;
-        config.phase_correct = true;
-        config.enable = true;
-        config.divider = self.divider.into();
-        config.compare_a = 0;
-        config.compare_b = 0;
-        config.top = self.top;
+    /// Applies a phase command: tri-states the bridge when disabled, otherwise PWMs the high
+    /// side with the low side driven complementary, `dead_time_ticks` apart.
+    pub(crate) fn apply(&mut self, cmd: PhaseCommand) {
+        if !cmd.enable {
+            self.pwm.set_config(&self.high_impedance_config);
 
-        self.pwm.set_config(&config);
+            let (high_pwm, low_pwm) = self.pwm.split_by_ref();
+            let _ = high_pwm.unwrap().set_duty_cycle_fully_off();
+            let _ = low_pwm.unwrap().set_duty_cycle_fully_off();
+            return;
+        }
+
+        self.pwm.set_config(&self.complementary_config);
 
         let (high_pwm, low_pwm) = self.pwm.split_by_ref();
+        let low_compare = (cmd.duty_cycle_high_side + self.dead_time_ticks).min(self.top);
+        let _ = high_pwm.unwrap().set_duty_cycle(cmd.duty_cycle_high_side);
+        let _ = low_pwm.unwrap().set_duty_cycle(low_compare);
+    }
+}
+
+/// Senses back-EMF zero-crossings on the floating phase of a sensorless BLDC motor and derives
+/// the commutation timing from the measured electrical period.
+struct BackEmfObserver<'d> {
+    adc: &'d SharedAdc,
+    phase_a: AdcChannel<'d>,
+    phase_b: AdcChannel<'d>,
+    phase_c: AdcChannel<'d>,
+    last_crossing: Option<Instant>,
+    intervals: filter::IntervalFilter<INTERVAL_FILTER_LEN>,
+}
 
-        let _ = high_pwm.unwrap().set_duty_cycle_fully_off();
-        let _ = low_pwm.unwrap().set_duty_cycle_fully_on();
+impl<'d> BackEmfObserver<'d> {
+    fn new(
+        adc: &'d SharedAdc,
+        phase_a: AdcChannel<'d>,
+        phase_b: AdcChannel<'d>,
+        phase_c: AdcChannel<'d>,
+    ) -> Self {
+        Self {
+            adc,
+            phase_a,
+            phase_b,
+            phase_c,
+            last_crossing: None,
+            intervals: filter::IntervalFilter::new(),
+        }
     }
 
-    /// Changes the half bridge to a high impedance output
-    fn set_high_impedance(&mut self) {
-        let mut config = Config::default();
-        config.invert_a = false;
-        config.invert_b = false;
-        config.phase_correct = true;
-        config.enable = true;
-        config.divider = self.divider.into();
-        config.compare_a = 0;
-        config.compare_b = 0;
-        config.top = self.top;
+    /// Samples all three phase terminals and returns `(floating, virtual_neutral)`, where the
+    /// virtual neutral is approximated as the average of the three terminal voltages.
+    async fn sample(&mut self, floating: Phase) -> (u16, u16) {
+        let mut adc = self.adc.lock().await;
+        let a = adc.read(&mut self.phase_a).await.unwrap_or(0);
+        let b = adc.read(&mut self.phase_b).await.unwrap_or(0);
+        let c = adc.read(&mut self.phase_c).await.unwrap_or(0);
+        drop(adc);
+
+        let neutral = ((a as u32 + b as u32 + c as u32) / 3) as u16;
+        let floating_sample = match floating {
+            Phase::A => a,
+            Phase::B => b,
+            Phase::C => c,
+        };
 
-        self.pwm.set_config(&config);
+        (floating_sample, neutral)
+    }
 
-        let (high_pwm, low_pwm) = self.pwm.split_by_ref();
+    /// Polls the floating phase until its terminal voltage crosses the virtual neutral in the
+    /// expected direction, or `timeout` elapses. Returns half of the filtered crossing period, or
+    /// `None` on a timeout or on the first crossing, which has no earlier crossing to measure from.
+    async fn wait_for_zero_crossing(
+        &mut self,
+        floating: Phase,
+        rising: bool,
+        timeout: Duration,
+    ) -> Option<Duration> {
+        let deadline = Instant::now() + timeout;
+
+        while Instant::now() < deadline {
+            let (floating_voltage, neutral) = self.sample(floating).await;
+
+            let crossed = if rising {
+                floating_voltage > neutral
+            } else {
+                floating_voltage < neutral
+            };
+
+            if crossed {
+                let now = Instant::now();
+                let half_interval = match self.last_crossing.replace(now) {
+                    Some(previous) => {
+                        let interval = now - previous;
+                        self.intervals.push(interval);
+                        self.intervals.median().unwrap_or(interval) / 2
+                    }
+                    None => return None,
+                };
+
+                return Some(half_interval);
+            }
+        }
 
-        let _ = high_pwm.unwrap().set_duty_cycle_fully_off();
-        let _ = low_pwm.unwrap().set_duty_cycle_fully_off();
+        None
     }
 }
 
+static BRIDGES: StaticCell<SharedBridges> = StaticCell::new();
+static SHARED_ADC: StaticCell<SharedAdc> = StaticCell::new();
+static FAULT_LATCH: fault::FaultLatch = fault::FaultLatch::new();
+
 #[embassy_executor::main]
 async fn main(spawner: Spawner) {
     let p = embassy_rp::init(Default::default());
@@ -196,6 +382,16 @@ async fn main(spawner: Spawner) {
         PIN_13,
         PIN_14,
         PIN_15,
+        PIN_16,
+        PIN_17,
+        PIN_18,
+        PIN_19,
+        PIN_20,
+        PIN_26,
+        PIN_27,
+        PIN_28,
+        PIN_29,
+        ADC,
         ..
     } = p;
 
@@ -203,49 +399,250 @@ async fn main(spawner: Spawner) {
     let half_bridge_b = HalfBridge::new(p.PWM_SLICE6, PIN_12, PIN_13, 25_000, 1000);
     let half_bridge_c = HalfBridge::new(p.PWM_SLICE7, PIN_14, PIN_15, 25_000, 1000);
 
-    let _ = spawner.spawn(bldc_driver_task(
+    let bridges: &'static SharedBridges = BRIDGES.init(BlockingMutex::new(RefCell::new((
         half_bridge_a,
         half_bridge_b,
         half_bridge_c,
+    ))));
+
+    let adc: &'static SharedAdc =
+        SHARED_ADC.init(AsyncMutex::new(Adc::new(ADC, Irqs, AdcConfig::default())));
+
+    // Prime the speed setpoint; an external input task can override it via `speed::SPEED_SETPOINT`.
+    speed::SPEED_SETPOINT.signal(DEFAULT_TARGET_ELECTRICAL_HZ);
+
+    let current_sense = AdcChannel::new_pin(PIN_29, Pull::None);
+    let overvoltage_comparator = Input::new(PIN_19, Pull::None);
+    let _ = spawner.spawn(fault::protection_task(
+        adc,
+        current_sense,
+        overvoltage_comparator,
+        bridges,
+        &FAULT_LATCH,
+        fault::ProtectionConfig::default(),
     ));
 
+    let reenable_button = Input::new(PIN_20, Pull::Up);
+    let _ = spawner.spawn(fault::fault_clear_task(reenable_button, &FAULT_LATCH));
+
+    match CONTROL_MODE {
+        ControlMode::SixStep => {
+            let phase_a_sense = AdcChannel::new_pin(PIN_26, Pull::None);
+            let phase_b_sense = AdcChannel::new_pin(PIN_27, Pull::None);
+            let phase_c_sense = AdcChannel::new_pin(PIN_28, Pull::None);
+            let back_emf = BackEmfObserver::new(adc, phase_a_sense, phase_b_sense, phase_c_sense);
+
+            let _ = spawner.spawn(bldc_driver_task(bridges, &FAULT_LATCH, back_emf));
+        }
+        ControlMode::Foc => {
+            let phase_a_current = AdcChannel::new_pin(PIN_26, Pull::None);
+            let phase_b_current = AdcChannel::new_pin(PIN_27, Pull::None);
+
+            let hall_a = Input::new(PIN_16, Pull::Up);
+            let hall_b = Input::new(PIN_17, Pull::Up);
+            let hall_c = Input::new(PIN_18, Pull::Up);
+            let hall_sensor = foc::HallAngleSensor::new(hall_a, hall_b, hall_c);
+
+            let _ = spawner.spawn(foc_driver_task(
+                bridges,
+                &FAULT_LATCH,
+                adc,
+                phase_a_current,
+                phase_b_current,
+                hall_sensor,
+            ));
+        }
+    }
+
     // Keep the on board LED in scope
     loop {
         embassy_time::Timer::after(Duration::from_secs(1)).await;
     }
 }
 
+/// Converts a measured half zero-crossing interval into an electrical speed in Hz (one
+/// electrical revolution takes `12 * half_interval`).
+fn electrical_hz_from_half_interval(half_interval: Duration) -> f32 {
+    let half_interval_secs = half_interval.as_micros() as f32 / 1_000_000.0;
+    1.0 / (12.0 * half_interval_secs)
+}
+
+/// Drives the three half bridges for one commutation step; the fault latch is not checked here
+fn apply_step(step: usize, table: &[InverterOutput; 6], bridges: &'static SharedBridges) {
+    let output = table[step];
+
+    bridges.lock(|cell| {
+        let mut bridges = cell.borrow_mut();
+        bridges.0.apply(output.phase_a);
+        bridges.1.apply(output.phase_b);
+        bridges.2.apply(output.phase_c);
+    });
+}
+
+/// Drives one commutation step like `apply_step`, but with the driven phase's duty replaced by
+/// `duty` raw timer counts.
+fn apply_step_with_duty(
+    step: usize,
+    table: &[InverterOutput; 6],
+    duty: u16,
+    bridges: &'static SharedBridges,
+) {
+    let output = table[step];
+    let scale = |cmd: PhaseCommand| -> PhaseCommand {
+        if cmd.enable && cmd.duty_cycle_high_side > 0 {
+            PhaseCommand::driven(duty)
+        } else {
+            cmd
+        }
+    };
+
+    bridges.lock(|cell| {
+        let mut bridges = cell.borrow_mut();
+        bridges.0.apply(scale(output.phase_a));
+        bridges.1.apply(scale(output.phase_b));
+        bridges.2.apply(scale(output.phase_c));
+    });
+}
+
 #[embassy_executor::task]
 async fn bldc_driver_task(
-    mut half_bridge_a: HalfBridge<'static, embassy_rp::peripherals::PWM_SLICE2>,
-    mut half_bridge_b: HalfBridge<'static, embassy_rp::peripherals::PWM_SLICE6>,
-    mut half_bridge_c: HalfBridge<'static, embassy_rp::peripherals::PWM_SLICE7>,
+    bridges: &'static SharedBridges,
+    fault: &'static fault::FaultLatch,
+    mut back_emf: BackEmfObserver<'static>,
 ) {
+    let max_duty = bridges.lock(|cell| cell.borrow().0.max_duty());
+    let min_run_duty = (max_duty as u32 * MIN_RUN_DUTY_PERCENT as u32 / 100) as u16;
+    let startup_duty = (max_duty as u32 * MAX_INVERTER_DUTY_CYCLE_PERCENT as u32 / 100) as u16;
+    let table = commutation_table(max_duty);
+
     let mut step: usize = 0;
-    let mut ticker = Ticker::every(Duration::from_millis(25));
 
-    loop {
-        step = (step + 1) % THREE_PHASE_COMMUTATION_TABLE.len();
+    // Open-loop startup ramp: align on the first step, then accelerate through the commutation
+    // table with a geometrically decreasing dwell time.
+    let mut dwell = STARTUP_DWELL;
+    apply_step(step, &table, bridges);
+    embassy_time::Timer::after(dwell).await;
+
+    while dwell > STARTUP_MIN_DWELL {
+        if fault.is_tripped() {
+            embassy_time::Timer::after(dwell).await;
+            continue;
+        }
+
+        step = (step + 1) % table.len();
+        apply_step(step, &table, bridges);
+        embassy_time::Timer::after(dwell).await;
+        dwell = dwell * STARTUP_RAMP_NUM / STARTUP_RAMP_DEN;
+    }
 
-        let output = THREE_PHASE_COMMUTATION_TABLE[step];
+    // Closed loop: each step is timed from the back-EMF zero-crossing on the floating phase,
+    // falling back to the last dwell on a timeout. Duty stays at the startup level until
+    // `ZERO_CROSSINGS_TO_LOCK` consecutive crossings are seen, then the speed controller takes
+    // over, regulating towards the setpoint published on `speed::SPEED_SETPOINT`.
+    let mut consistent_crossings: u8 = 0;
+    let mut fallback_dwell = STARTUP_MIN_DWELL;
+    let mut ticker_fallback = Ticker::every(fallback_dwell);
+    let mut speed_controller = speed::SpeedController::new(
+        SPEED_KP,
+        SPEED_KI,
+        max_duty,
+        min_run_duty,
+        SPEED_MAX_ACCEL_HZ_PER_SEC,
+    );
+    let mut target_hz = DEFAULT_TARGET_ELECTRICAL_HZ;
+    let mut duty = startup_duty;
 
-        match output.phase_a {
-            PhaseState::HighDutyCycle(percentage) => half_bridge_a.set_high(percentage),
-            PhaseState::Low => half_bridge_a.set_low(),
-            PhaseState::HighImpedance => half_bridge_a.set_high_impedance(),
+    loop {
+        if fault.is_tripped() {
+            ticker_fallback.next().await;
+            continue;
+        }
+
+        step = (step + 1) % table.len();
+        apply_step_with_duty(step, &table, duty, bridges);
+
+        let (floating, rising) = ZERO_CROSSING_TABLE[step];
+        let dwell = match back_emf
+            .wait_for_zero_crossing(floating, rising, ZERO_CROSSING_TIMEOUT)
+            .await
+        {
+            Some(half_interval) => {
+                consistent_crossings = (consistent_crossings + 1).min(ZERO_CROSSINGS_TO_LOCK);
+                fallback_dwell = half_interval;
+                ticker_fallback = Ticker::every(fallback_dwell);
+                embassy_time::Timer::after(half_interval).await;
+                half_interval
+            }
+            None => {
+                // Lost sync: fall back to the last trusted interval rather than stalling
+                consistent_crossings = 0;
+                ticker_fallback.next().await;
+                fallback_dwell
+            }
         };
 
-        match output.phase_b {
-            PhaseState::HighDutyCycle(percentage) => half_bridge_b.set_high(percentage),
-            PhaseState::Low => half_bridge_b.set_low(),
-            PhaseState::HighImpedance => half_bridge_b.set_high_impedance(),
+        if consistent_crossings >= ZERO_CROSSINGS_TO_LOCK {
+            if let Some(setpoint) = speed::SPEED_SETPOINT.try_take() {
+                target_hz = setpoint;
+            }
+
+            let measured_hz = electrical_hz_from_half_interval(dwell);
+            let dt = dwell.as_micros() as f32 / 1_000_000.0;
+            duty = speed_controller.update(target_hz, measured_hz, dt);
+        } else {
+            duty = startup_duty;
+        }
+    }
+}
+
+#[embassy_executor::task]
+async fn foc_driver_task(
+    bridges: &'static SharedBridges,
+    fault: &'static fault::FaultLatch,
+    adc: &'static SharedAdc,
+    mut phase_a_current: AdcChannel<'static>,
+    mut phase_b_current: AdcChannel<'static>,
+    mut hall_sensor: foc::HallAngleSensor<'static>,
+) {
+    let mut controller = foc::FocController::new(FOC_KP, FOC_KI, BUS_VOLTAGE / 2.0);
+    let dt = FOC_PERIOD.as_micros() as f32 / 1_000_000.0;
+    let mut ticker = Ticker::every(FOC_PERIOD);
+    let max_duty = bridges.lock(|cell| cell.borrow().0.max_duty()) as f32;
+
+    loop {
+        match embassy_futures::select::select(hall_sensor.wait_for_edge(), ticker.next()).await {
+            embassy_futures::select::Either::First(()) => continue,
+            embassy_futures::select::Either::Second(()) => {}
+        }
+
+        if fault.is_tripped() {
+            continue;
+        }
+
+        let Some(theta) = hall_sensor.angle(Instant::now()) else {
+            continue;
         };
 
-        match output.phase_c {
-            PhaseState::HighDutyCycle(percentage) => half_bridge_c.set_high(percentage),
-            PhaseState::Low => half_bridge_c.set_low(),
-            PhaseState::HighImpedance => half_bridge_c.set_high_impedance(),
+        let (i_a, i_b) = {
+            let mut adc = adc.lock().await;
+            let i_a = adc.read(&mut phase_a_current).await.unwrap_or(0);
+            let i_b = adc.read(&mut phase_b_current).await.unwrap_or(0);
+            (i_a, i_b)
         };
-        ticker.next().await;
+
+        let duties = controller.update(i_a as f32, i_b as f32, theta, FOC_IQ_REFERENCE, BUS_VOLTAGE, dt);
+
+        bridges.lock(|cell| {
+            let mut bridges = cell.borrow_mut();
+            bridges
+                .0
+                .apply(PhaseCommand::driven((duties.phase_a * max_duty) as u16));
+            bridges
+                .1
+                .apply(PhaseCommand::driven((duties.phase_b * max_duty) as u16));
+            bridges
+                .2
+                .apply(PhaseCommand::driven((duties.phase_c * max_duty) as u16));
+        });
     }
 }
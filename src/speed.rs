@@ -0,0 +1,70 @@
+//! Closed-loop electrical speed control: regulates the commutation duty cycle towards a
+//! commandable target electrical speed instead of driving the inverter at a fixed duty.
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+
+use crate::foc::PiRegulator;
+
+/// Target electrical speed in Hz, updated at runtime by an external input task and read back
+/// by the driver task.
+pub(crate) static SPEED_SETPOINT: Signal<CriticalSectionRawMutex, f32> = Signal::new();
+
+/// Slews a value towards a target at a bounded rate.
+pub(crate) struct RampLimiter {
+    max_rate_per_sec: f32,
+    value: f32,
+}
+
+impl RampLimiter {
+    pub(crate) const fn new(max_rate_per_sec: f32, initial: f32) -> Self {
+        Self {
+            max_rate_per_sec,
+            value: initial,
+        }
+    }
+
+    /// Advances the ramped value towards `target` by at most `max_rate_per_sec * dt`
+    pub(crate) fn advance(&mut self, target: f32, dt: f32) -> f32 {
+        let max_step = self.max_rate_per_sec * dt;
+        self.value += (target - self.value).clamp(-max_step, max_step);
+        self.value
+    }
+}
+
+/// Closed-loop electrical speed controller: ramps the commanded setpoint, then regulates the
+/// error against the measured electrical speed with a PI regulator bounded to
+/// `min_duty..=max_duty`.
+pub(crate) struct SpeedController {
+    regulator: PiRegulator,
+    ramp: RampLimiter,
+}
+
+impl SpeedController {
+    pub(crate) const fn new(
+        kp: f32,
+        ki: f32,
+        max_duty: u16,
+        min_duty: u16,
+        max_accel_hz_per_sec: f32,
+    ) -> Self {
+        Self {
+            regulator: PiRegulator::new(kp, ki, min_duty as f32, max_duty as f32),
+            ramp: RampLimiter::new(max_accel_hz_per_sec, 0.0),
+        }
+    }
+
+    /// Runs one control period: ramps `target_hz`, regulates against `measured_hz`, and returns
+    /// the commanded high-side duty in raw timer counts (zero once the ramped setpoint reaches
+    /// zero).
+    pub(crate) fn update(&mut self, target_hz: f32, measured_hz: f32, dt: f32) -> u16 {
+        let ramped_target = self.ramp.advance(target_hz, dt);
+
+        if ramped_target <= 0.0 {
+            self.regulator.reset();
+            return 0;
+        }
+
+        self.regulator.update(ramped_target - measured_hz, dt) as u16
+    }
+}